@@ -0,0 +1,19 @@
+use std::process::Command;
+
+// Bakes the current git commit into the binary via `GIT_COMMIT_HASH` so
+// `/metrics/info` can report which build is running. Falls back to
+// "unknown" when the command fails (e.g. building from a tarball with no
+// `.git` directory) rather than failing the build.
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=GIT_COMMIT_HASH={}", commit);
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+}
@@ -1,23 +1,1087 @@
 use actix_web::{web, App, HttpServer, HttpResponse, Responder, HttpRequest};
-use actix_web::http::header;
+use actix_web::http::{header, HeaderMap, Method, StatusCode};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use std::future::{ready, Ready};
+use futures_util::future::LocalBoxFuture;
 use actix_cors::Cors;
 use serde::{Serialize, Deserialize};
-use sysinfo::System; // Use only the System import, no trait
-use std::sync::{Arc, Mutex, RwLock};
+use sysinfo::{Disks, Networks, Pid, ProcessesToUpdate, System};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, Weak};
+use std::sync::atomic::AtomicU64;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::collections::HashMap;
+use std::net::IpAddr;
 use awc::Client;
 use chrono::Timelike; // Add this import for hour() method
+use prometheus_client::encoding::text::encode;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Registry;
+use rusqlite::Connection;
+use async_trait::async_trait;
+use cadence::{StatsdClient, UdpMetricSink, Counted, Timed};
+use ulid::Ulid;
+use tokio::sync::broadcast;
+use futures_util::stream;
 
 // Node.js backend URL
 const BACKEND_URL: &str = "http://localhost:3001";
 
+// How often the rollup task snapshots in-memory metrics into SQLite.
+const ROLLUP_INTERVAL_SECS: u64 = 60;
+
 // Shared application state
 struct AppState {
     system: Mutex<System>,
-    token_metrics: RwLock<TokenMetrics>,
+    token_metrics: Arc<RwLock<TokenMetrics>>,
     traffic_metrics: RwLock<TrafficMetrics>,
     start_time: SystemTime,
+    registry: Mutex<Registry>,
+    prom: PrometheusMetrics,
+    rate_limiter: RateLimiter,
+    metrics_db: Mutex<Connection>,
+    filters: Vec<Arc<dyn ProxyFilter>>,
+    statsd: Option<StatsdClient>,
+    instance_id: String,
+    startup_utc: u64,
+    machine_id: Option<String>,
+    metrics_stream: broadcast::Sender<String>,
+    endpoint_stats: Mutex<HashMap<(Method, String, u16), EndpointStat>>,
+    // Previous per-interface (bytes_received, bytes_transmitted) and when
+    // they were captured, so /metrics/host can report rates instead of
+    // raw cumulative counters.
+    prev_network_counters: Mutex<HashMap<String, (u64, u64)>>,
+    prev_network_refresh: Mutex<Instant>,
+    alert_rules: RwLock<HashMap<String, AlertRule>>,
+    alert_runtime: Mutex<HashMap<String, AlertRuntimeState>>,
+}
+
+#[derive(Serialize)]
+struct ProcessMetrics {
+    pid: u32,
+    rss_bytes: u64,
+    cpu_usage: f32,
+}
+
+#[derive(Serialize)]
+struct DiskMetrics {
+    mount_point: String,
+    total_bytes: u64,
+    used_bytes: u64,
+    free_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct NetworkInterfaceMetrics {
+    interface: String,
+    bytes_in_per_sec: f64,
+    bytes_out_per_sec: f64,
+}
+
+#[derive(Serialize)]
+struct HostMetrics {
+    process: ProcessMetrics,
+    disks: Vec<DiskMetrics>,
+    network: Vec<NetworkInterfaceMetrics>,
+}
+
+// Reports telemetry about this server's own process plus host disks and
+// network interfaces, complementing the global CPU/memory numbers in
+// `/metrics/system`.
+async fn host_metrics(data: web::Data<Arc<AppState>>) -> impl Responder {
+    let pid = Pid::from_u32(std::process::id());
+
+    let process = {
+        let mut system = data.system.lock().unwrap();
+        system.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+        match system.process(pid) {
+            Some(p) => ProcessMetrics {
+                pid: pid.as_u32(),
+                rss_bytes: p.memory(),
+                cpu_usage: p.cpu_usage(),
+            },
+            None => ProcessMetrics { pid: pid.as_u32(), rss_bytes: 0, cpu_usage: 0.0 },
+        }
+    };
+
+    let disks: Vec<DiskMetrics> = Disks::new_with_refreshed_list()
+        .iter()
+        .map(|disk| DiskMetrics {
+            mount_point: disk.mount_point().to_string_lossy().to_string(),
+            total_bytes: disk.total_space(),
+            used_bytes: disk.total_space().saturating_sub(disk.available_space()),
+            free_bytes: disk.available_space(),
+        })
+        .collect();
+
+    let networks = Networks::new_with_refreshed_list();
+    let now = Instant::now();
+
+    let mut prev_counters = data.prev_network_counters.lock().unwrap();
+    let mut prev_refresh = data.prev_network_refresh.lock().unwrap();
+    let elapsed_secs = now.duration_since(*prev_refresh).as_secs_f64().max(0.001);
+
+    let network: Vec<NetworkInterfaceMetrics> = networks
+        .iter()
+        .map(|(name, iface)| {
+            let (bytes_in, bytes_out) = (iface.total_received(), iface.total_transmitted());
+            let (prev_in, prev_out) = prev_counters.get(name).copied().unwrap_or((bytes_in, bytes_out));
+
+            let metrics = NetworkInterfaceMetrics {
+                interface: name.clone(),
+                bytes_in_per_sec: bytes_in.saturating_sub(prev_in) as f64 / elapsed_secs,
+                bytes_out_per_sec: bytes_out.saturating_sub(prev_out) as f64 / elapsed_secs,
+            };
+
+            prev_counters.insert(name.clone(), (bytes_in, bytes_out));
+            metrics
+        })
+        .collect();
+
+    *prev_refresh = now;
+
+    HttpResponse::Ok().json(HostMetrics { process, disks, network })
+}
+
+// Fixed latency boundaries (ms) for the per-endpoint histogram tracked by
+// `HttpMetricsMiddleware`. The implicit final bucket is "> 1000ms".
+const HTTP_BUCKET_BOUNDS_MS: [f64; 7] = [1.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0];
+
+struct EndpointStat {
+    count: u64,
+    buckets: [u64; HTTP_BUCKET_BOUNDS_MS.len() + 1],
+}
+
+impl Default for EndpointStat {
+    fn default() -> Self {
+        EndpointStat {
+            count: 0,
+            buckets: [0; HTTP_BUCKET_BOUNDS_MS.len() + 1],
+        }
+    }
+}
+
+impl EndpointStat {
+    fn record(&mut self, elapsed_ms: f64) {
+        self.count += 1;
+        let idx = HTTP_BUCKET_BOUNDS_MS
+            .iter()
+            .position(|&bound| elapsed_ms <= bound)
+            .unwrap_or(HTTP_BUCKET_BOUNDS_MS.len());
+        self.buckets[idx] += 1;
+    }
+}
+
+/// Records a request counter and latency histogram for every route the
+/// server handles (not just proxied traffic), keyed by method + matched
+/// path + status code.
+struct HttpMetricsMiddleware {
+    state: Arc<AppState>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for HttpMetricsMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = HttpMetricsMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(HttpMetricsMiddlewareService {
+            service,
+            state: Arc::clone(&self.state),
+        }))
+    }
+}
+
+struct HttpMetricsMiddlewareService<S> {
+    service: S,
+    state: Arc<AppState>,
+}
+
+impl<S, B> Service<ServiceRequest> for HttpMetricsMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().clone();
+        let path = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+        let state = Arc::clone(&self.state);
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+            let status = res.status().as_u16();
+
+            let mut stats = state.endpoint_stats.lock().unwrap();
+            stats.entry((method, path, status)).or_default().record(elapsed);
+
+            Ok(res)
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct EndpointStatView {
+    method: String,
+    path: String,
+    status: u16,
+    count: u64,
+    latency_buckets_ms: HashMap<String, u64>,
+}
+
+// Exposes the per-endpoint request counters and latency histograms the
+// middleware accumulates.
+async fn http_metrics(state: web::Data<Arc<AppState>>) -> impl Responder {
+    let stats = state.endpoint_stats.lock().unwrap();
+
+    let view: Vec<EndpointStatView> = stats
+        .iter()
+        .map(|((method, path, status), stat)| {
+            let mut latency_buckets_ms = HashMap::new();
+            for (i, &bound) in HTTP_BUCKET_BOUNDS_MS.iter().enumerate() {
+                latency_buckets_ms.insert(format!("le_{}", bound as u64), stat.buckets[i]);
+            }
+            latency_buckets_ms.insert("le_inf".to_string(), stat.buckets[HTTP_BUCKET_BOUNDS_MS.len()]);
+
+            EndpointStatView {
+                method: method.to_string(),
+                path: path.clone(),
+                status: *status,
+                count: stat.count,
+                latency_buckets_ms,
+            }
+        })
+        .collect();
+
+    HttpResponse::Ok().json(view)
+}
+
+// Bounded so a slow/disconnected SSE client drops the oldest frame instead
+// of applying backpressure to the publisher.
+const METRICS_STREAM_CHANNEL_CAPACITY: usize = 32;
+
+/// Background task: publishes a fresh `SystemMetrics` frame to
+/// `metrics_stream` on a fixed interval (configurable via
+/// `METRICS_STREAM_INTERVAL_SECS`), in addition to the immediate frames
+/// `update_token_metrics` publishes on its own.
+async fn metrics_stream_task(state: Arc<AppState>) {
+    let interval_secs = std::env::var("METRICS_STREAM_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(5);
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        let sys = {
+            let mut system = state.system.lock().unwrap();
+            system.refresh_all();
+            build_system_metrics(&system, &state.start_time, &state.instance_id)
+        };
+
+        if let Ok(json) = serde_json::to_string(&sys) {
+            let _ = state.metrics_stream.send(json);
+        }
+    }
+}
+
+// Streams `SystemMetrics`/`TokenMetrics` frames over Server-Sent Events so
+// dashboards get real-time updates without polling.
+async fn metrics_stream_handler(state: web::Data<Arc<AppState>>) -> impl Responder {
+    let rx = state.metrics_stream.subscribe();
+
+    let body = stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(json) => {
+                    let frame = web::Bytes::from(format!("data: {}\n\n", json));
+                    return Some((Ok::<_, actix_web::Error>(frame), rx));
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(body)
+}
+
+/// Best-effort read of the host's machine ID, so dashboards can tell replicas
+/// on the same host apart from replicas on different hosts. Absent on
+/// platforms without `/etc/machine-id` (e.g. inside some containers).
+fn read_machine_id() -> Option<String> {
+    std::fs::read_to_string("/etc/machine-id")
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+#[derive(Serialize)]
+struct InstanceInfo {
+    instance_id: String,
+    startup_utc: u64,
+    machine_id: Option<String>,
+    crate_version: &'static str,
+    git_version: &'static str,
+}
+
+/// Builds a StatsD client from `STATSD_HOST` (host:port), or `None` if the
+/// env var isn't set so the emitter is a no-op by default.
+fn build_statsd_client() -> Option<StatsdClient> {
+    let host = std::env::var("STATSD_HOST").ok()?;
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.set_nonblocking(true).ok()?;
+
+    let sink = match UdpMetricSink::from(host.as_str(), socket) {
+        Ok(sink) => sink,
+        Err(err) => {
+            println!("❌ Failed to create StatsD sink for {}: {}", host, err);
+            return None;
+        }
+    };
+
+    Some(StatsdClient::from_sink("nexa_metrics_service", sink))
+}
+
+/// One rolled-up interval of metrics, persisted so history survives restarts.
+struct MetricsRollup {
+    bucket_start: i64,
+    request_count: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    status_counts: HashMap<u16, u64>,
+    model_token_totals: HashMap<String, u64>,
+    latency_percentiles: HashMap<String, LatencyPercentiles>,
+}
+
+// How far back `token_system_samples` rows are kept before being pruned.
+const METRICS_RETENTION_SECS_DEFAULT: i64 = 7 * 24 * 3600;
+
+fn open_metrics_db() -> rusqlite::Result<Connection> {
+    let conn = Connection::open("metrics_history.db")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS metrics_rollups (
+            bucket_start INTEGER PRIMARY KEY,
+            request_count INTEGER NOT NULL,
+            bytes_in INTEGER NOT NULL,
+            bytes_out INTEGER NOT NULL,
+            status_counts TEXT NOT NULL,
+            model_token_totals TEXT NOT NULL,
+            latency_percentiles TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS token_system_samples (
+            ts INTEGER PRIMARY KEY,
+            cpu_usage_percent REAL NOT NULL,
+            memory_used INTEGER NOT NULL,
+            model_tokens TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS alert_rules (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            condition TEXT NOT NULL,
+            webhook_url TEXT NOT NULL,
+            debounce_secs INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(conn)
+}
+
+fn insert_token_system_sample(
+    conn: &Connection,
+    ts: i64,
+    cpu_usage_percent: f32,
+    memory_used: u64,
+    model_tokens: &HashMap<String, u64>,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO token_system_samples (ts, cpu_usage_percent, memory_used, model_tokens)
+         VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![
+            ts,
+            cpu_usage_percent as f64,
+            memory_used as i64,
+            serde_json::to_string(model_tokens).unwrap_or_default(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Prunes samples older than `retention_secs` so the database doesn't grow
+/// unbounded.
+fn prune_token_system_samples(conn: &Connection, now: i64, retention_secs: i64) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM token_system_samples WHERE ts < ?1",
+        rusqlite::params![now - retention_secs],
+    )
+}
+
+fn insert_rollup(conn: &Connection, rollup: &MetricsRollup) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO metrics_rollups
+            (bucket_start, request_count, bytes_in, bytes_out, status_counts, model_token_totals, latency_percentiles)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![
+            rollup.bucket_start,
+            rollup.request_count as i64,
+            rollup.bytes_in as i64,
+            rollup.bytes_out as i64,
+            serde_json::to_string(&rollup.status_counts).unwrap_or_default(),
+            serde_json::to_string(&rollup.model_token_totals).unwrap_or_default(),
+            serde_json::to_string(&rollup.latency_percentiles).unwrap_or_default(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Periodically snapshots `traffic_metrics`/`token_metrics` and writes a
+/// rollup row, so `/metrics/history` has data across restarts. Runs for the
+/// lifetime of the process.
+async fn rollup_task(state: Arc<AppState>) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(ROLLUP_INTERVAL_SECS));
+
+    loop {
+        interval.tick().await;
+
+        let bucket_start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+            .as_secs() as i64;
+
+        let traffic = state.traffic_metrics.read().unwrap().clone();
+        let tokens = state.token_metrics.read().unwrap().clone();
+
+        let sys = {
+            let mut system = state.system.lock().unwrap();
+            system.refresh_all();
+            build_system_metrics(&system, &state.start_time, &state.instance_id)
+        };
+
+        let latency_percentiles = traffic.response_time_histograms
+            .iter()
+            .map(|(path, histogram)| (path.clone(), histogram.percentiles()))
+            .collect();
+
+        let rollup = MetricsRollup {
+            bucket_start,
+            request_count: traffic.total_requests,
+            bytes_in: traffic.total_bytes_in,
+            bytes_out: traffic.total_bytes_out,
+            status_counts: traffic.responses_by_status,
+            model_token_totals: tokens.by_model.clone(),
+            latency_percentiles,
+        };
+
+        let conn = state.metrics_db.lock().unwrap();
+        if let Err(err) = insert_rollup(&conn, &rollup) {
+            println!("❌ Failed to persist metrics rollup: {}", err);
+        }
+        if let Err(err) = insert_token_system_sample(&conn, bucket_start, sys.cpu.usage, sys.memory.used, &tokens.by_model) {
+            println!("❌ Failed to persist token/system sample: {}", err);
+        }
+
+        let retention_secs = std::env::var("METRICS_RETENTION_SECS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(METRICS_RETENTION_SECS_DEFAULT);
+        if let Err(err) = prune_token_system_samples(&conn, bucket_start, retention_secs) {
+            println!("❌ Failed to prune token/system samples: {}", err);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    from: Option<i64>,
+    to: Option<i64>,
+    bucket: Option<i64>,
+}
+
+#[derive(Serialize, Default)]
+struct HistoryBucket {
+    bucket_start: i64,
+    request_count: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+// Queries persisted rollups in `[from, to)` and re-buckets them into
+// `bucket`-second windows.
+async fn metrics_history(
+    state: web::Data<Arc<AppState>>,
+    query: web::Query<HistoryQuery>,
+) -> impl Responder {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs() as i64;
+
+    let from = query.from.unwrap_or(now - 24 * 3600);
+    let to = query.to.unwrap_or(now);
+    let bucket_width = query.bucket.unwrap_or(3600).max(1);
+
+    let conn = state.metrics_db.lock().unwrap();
+
+    // `metrics_rollups` stores lifetime-cumulative snapshots (the traffic
+    // counters are never reset), so the per-interval contribution of a row is
+    // its delta from the immediately preceding row, not the row itself.
+    // Seed that delta with the last snapshot before `from` so the first row
+    // inside the window is also reported as a proper interval, not its whole
+    // lifetime total.
+    let baseline: Option<(u64, u64, u64)> = conn
+        .query_row(
+            "SELECT request_count, bytes_in, bytes_out FROM metrics_rollups
+             WHERE bucket_start < ?1 ORDER BY bucket_start DESC LIMIT 1",
+            rusqlite::params![from],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)? as u64,
+                    row.get::<_, i64>(1)? as u64,
+                    row.get::<_, i64>(2)? as u64,
+                ))
+            },
+        )
+        .ok();
+
+    let mut stmt = match conn.prepare(
+        "SELECT bucket_start, request_count, bytes_in, bytes_out
+         FROM metrics_rollups WHERE bucket_start >= ?1 AND bucket_start < ?2
+         ORDER BY bucket_start ASC",
+    ) {
+        Ok(stmt) => stmt,
+        Err(err) => {
+            println!("❌ Failed to query metrics history: {}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let rows = stmt.query_map(rusqlite::params![from, to], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, i64>(1)? as u64,
+            row.get::<_, i64>(2)? as u64,
+            row.get::<_, i64>(3)? as u64,
+        ))
+    });
+
+    let rows = match rows {
+        Ok(rows) => rows,
+        Err(err) => {
+            println!("❌ Failed to read metrics history rows: {}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let mut buckets: HashMap<i64, HistoryBucket> = HashMap::new();
+    let mut prev = baseline;
+    for row in rows.flatten() {
+        let (bucket_start, request_count, bytes_in, bytes_out) = row;
+
+        // A counter reset (e.g. process restart) can make the snapshot
+        // smaller than the previous one; treat that as a fresh baseline of
+        // zero rather than underflowing.
+        let delta = |cur: u64, last: u64| if cur >= last { cur - last } else { cur };
+        let (d_requests, d_bytes_in, d_bytes_out) = match prev {
+            Some((p_requests, p_bytes_in, p_bytes_out)) => (
+                delta(request_count, p_requests),
+                delta(bytes_in, p_bytes_in),
+                delta(bytes_out, p_bytes_out),
+            ),
+            None => (0, 0, 0),
+        };
+        prev = Some((request_count, bytes_in, bytes_out));
+
+        let window_start = bucket_start - (bucket_start % bucket_width);
+        let entry = buckets.entry(window_start).or_insert_with(|| HistoryBucket {
+            bucket_start: window_start,
+            ..Default::default()
+        });
+        entry.request_count += d_requests;
+        entry.bytes_in += d_bytes_in;
+        entry.bytes_out += d_bytes_out;
+    }
+
+    let mut series: Vec<HistoryBucket> = buckets.into_values().collect();
+    series.sort_by_key(|b| b.bucket_start);
+
+    HttpResponse::Ok().json(series)
+}
+
+#[derive(Serialize, Default)]
+struct TokenHistoryBucket {
+    bucket_start: i64,
+    total_tokens: u64,
+    by_model: HashMap<String, u64>,
+    avg_cpu_usage_percent: f64,
+    avg_memory_used: u64,
+}
+
+// Queries persisted token/system samples in `[from, to)` and re-buckets them
+// into `bucket`-second windows, summing per-model token counts and
+// averaging the system gauges within each window.
+async fn metrics_tokens_history(
+    state: web::Data<Arc<AppState>>,
+    query: web::Query<HistoryQuery>,
+) -> impl Responder {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs() as i64;
+
+    let from = query.from.unwrap_or(now - 24 * 3600);
+    let to = query.to.unwrap_or(now);
+    let bucket_width = query.bucket.unwrap_or(3600).max(1);
+
+    let conn = state.metrics_db.lock().unwrap();
+
+    // Like `metrics_rollups`, `model_tokens` on each sample is a
+    // lifetime-cumulative snapshot (see `update_token_metrics`), so the
+    // per-interval contribution is the per-model delta from the previous
+    // sample, not the sample itself. Seed the delta with the last sample
+    // before `from` so the first row inside the window isn't reported as its
+    // whole lifetime total.
+    let baseline: Option<HashMap<String, u64>> = conn
+        .query_row(
+            "SELECT model_tokens FROM token_system_samples
+             WHERE ts < ?1 ORDER BY ts DESC LIMIT 1",
+            rusqlite::params![from],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+        .map(|json| serde_json::from_str(&json).unwrap_or_default());
+
+    let mut stmt = match conn.prepare(
+        "SELECT ts, cpu_usage_percent, memory_used, model_tokens
+         FROM token_system_samples WHERE ts >= ?1 AND ts < ?2
+         ORDER BY ts ASC",
+    ) {
+        Ok(stmt) => stmt,
+        Err(err) => {
+            println!("❌ Failed to query token history: {}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let rows = stmt.query_map(rusqlite::params![from, to], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, f64>(1)?,
+            row.get::<_, i64>(2)? as u64,
+            row.get::<_, String>(3)?,
+        ))
+    });
+
+    let rows = match rows {
+        Ok(rows) => rows,
+        Err(err) => {
+            println!("❌ Failed to read token history rows: {}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    struct Accumulator {
+        bucket: TokenHistoryBucket,
+        sample_count: u64,
+        cpu_sum: f64,
+        memory_sum: u64,
+    }
+
+    let mut buckets: HashMap<i64, Accumulator> = HashMap::new();
+    let mut prev = baseline;
+    for row in rows.flatten() {
+        let (ts, cpu_usage_percent, memory_used, model_tokens_json) = row;
+        let model_tokens: HashMap<String, u64> = serde_json::from_str(&model_tokens_json).unwrap_or_default();
+        let window_start = ts - (ts % bucket_width);
+
+        let acc = buckets.entry(window_start).or_insert_with(|| Accumulator {
+            bucket: TokenHistoryBucket { bucket_start: window_start, ..Default::default() },
+            sample_count: 0,
+            cpu_sum: 0.0,
+            memory_sum: 0,
+        });
+
+        // A counter reset (e.g. process restart) can make a model's total
+        // smaller than the previous sample; treat that as a fresh baseline
+        // of zero rather than underflowing.
+        for (model, total) in &model_tokens {
+            let prev_total = prev.as_ref().and_then(|p| p.get(model)).copied().unwrap_or(*total);
+            let delta = if *total >= prev_total { total - prev_total } else { *total };
+            *acc.bucket.by_model.entry(model.clone()).or_insert(0) += delta;
+            acc.bucket.total_tokens += delta;
+        }
+        prev = Some(model_tokens);
+
+        acc.cpu_sum += cpu_usage_percent;
+        acc.memory_sum += memory_used;
+        acc.sample_count += 1;
+    }
+
+    let mut series: Vec<TokenHistoryBucket> = buckets
+        .into_values()
+        .map(|mut acc| {
+            if acc.sample_count > 0 {
+                acc.bucket.avg_cpu_usage_percent = acc.cpu_sum / acc.sample_count as f64;
+                acc.bucket.avg_memory_used = acc.memory_sum / acc.sample_count;
+            }
+            acc.bucket
+        })
+        .collect();
+    series.sort_by_key(|b| b.bucket_start);
+
+    HttpResponse::Ok().json(series)
+}
+
+// Token-bucket rate limiter keyed by API key (or client IP when no key is
+// present). Buckets are created lazily on first use and refilled based on
+// elapsed wall-clock time rather than a background ticker.
+struct RateLimiter {
+    buckets: RwLock<HashMap<String, Bucket>>,
+    tiers: HashMap<String, RateLimitTier>,
+    default_tier: RateLimitTier,
+}
+
+#[derive(Clone, Copy)]
+struct RateLimitTier {
+    capacity: f64,
+    refill_rate: f64, // tokens per second
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new() -> Self {
+        // Per-key overrides can be layered in here as tiers are assigned to
+        // customers; unknown keys fall back to `default_tier`.
+        let tiers = HashMap::new();
+
+        RateLimiter {
+            buckets: RwLock::new(HashMap::new()),
+            tiers,
+            default_tier: RateLimitTier {
+                capacity: 60.0,
+                refill_rate: 1.0, // 1 token/sec == 60 req/min sustained
+            },
+        }
+    }
+
+    /// Returns `Ok(())` if the request may proceed, or `Err(retry_after_secs)`
+    /// if the caller is over budget.
+    fn check(&self, key: &str) -> Result<(), f64> {
+        let tier = self.tiers.get(key).copied().unwrap_or(self.default_tier);
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.write().unwrap();
+        let bucket = buckets.entry(key.to_string()).or_insert_with(|| Bucket {
+            tokens: tier.capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * tier.refill_rate).min(tier.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err((1.0 - bucket.tokens) / tier.refill_rate)
+        }
+    }
+
+    /// Drops buckets that haven't been touched in `max_idle`. `rate_limit_key`
+    /// falls back to whatever string a client sends, so without this the map
+    /// grows one entry per distinct key/IP ever seen and never shrinks.
+    fn sweep_stale(&self, max_idle: Duration) {
+        let now = Instant::now();
+        self.buckets
+            .write()
+            .unwrap()
+            .retain(|_, bucket| now.duration_since(bucket.last_refill) < max_idle);
+    }
+}
+
+/// Resolve the identity a request should be rate-limited under: the
+/// `Authorization`/`X-API-Key` header if present, otherwise the client IP.
+fn rate_limit_key(req: &HttpRequest) -> String {
+    if let Some(value) = req.headers().get("X-API-Key") {
+        if let Ok(key) = value.to_str() {
+            return key.to_string();
+        }
+    }
+
+    if let Some(value) = req.headers().get(header::AUTHORIZATION) {
+        if let Ok(auth) = value.to_str() {
+            return auth.trim_start_matches("Bearer ").to_string();
+        }
+    }
+
+    req.peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// Prometheus-backed mirrors of the metrics above. The counters are updated
+// alongside `traffic_metrics`/`token_metrics`; the system gauges are set by
+// `prometheus_metrics_handler` on each scrape, just before it encodes them.
+struct PrometheusMetrics {
+    cpu_usage_percent: Gauge<f64, AtomicU64>,
+    memory_used_bytes: Gauge,
+    memory_total_bytes: Gauge,
+    memory_usage_percent: Gauge<f64, AtomicU64>,
+    uptime_seconds: Gauge,
+    total_requests: Counter,
+    total_bytes_in: Counter,
+    total_bytes_out: Counter,
+    responses_by_status: Family<StatusLabels, Counter>,
+    requests_by_endpoint: Family<EndpointLabels, Counter>,
+    token_total_processed: Family<ModelLabels, Counter>,
+    tokens_total: Family<ModelDirectionLabels, Counter>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct StatusLabels {
+    status: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct EndpointLabels {
+    path: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ModelLabels {
+    model: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ModelDirectionLabels {
+    model: String,
+    direction: String, // "input" | "output"
+}
+
+// Exponentially-spaced bucket upper bounds (ms) used for per-path latency
+// histograms. The final, implicit bucket is "> last bound".
+const LATENCY_BUCKET_BOUNDS_MS: [f64; 12] =
+    [1.0, 2.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0];
+
+/// Streaming latency histogram with a fixed set of buckets. Avoids keeping
+/// raw samples around so memory stays bounded regardless of traffic volume.
+#[derive(Clone)]
+struct LatencyHistogram {
+    counts: [u64; LATENCY_BUCKET_BOUNDS_MS.len() + 1],
+    sum_ms: f64,
+    max_ms: f64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            counts: [0; LATENCY_BUCKET_BOUNDS_MS.len() + 1],
+            sum_ms: 0.0,
+            max_ms: 0.0,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy)]
+struct LatencyPercentiles {
+    p50: f64,
+    p90: f64,
+    p95: f64,
+    p99: f64,
+    max: f64,
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, elapsed_ms: f64) {
+        let idx = LATENCY_BUCKET_BOUNDS_MS
+            .iter()
+            .position(|&bound| elapsed_ms <= bound)
+            .unwrap_or(LATENCY_BUCKET_BOUNDS_MS.len());
+        self.counts[idx] += 1;
+        self.sum_ms += elapsed_ms;
+        self.max_ms = self.max_ms.max(elapsed_ms);
+    }
+
+    fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Walks buckets accumulating counts until passing `rank = ceil(q * total)`,
+    /// then linearly interpolates within the winning bucket.
+    fn percentile(&self, q: f64) -> f64 {
+        let total = self.total();
+        if total == 0 {
+            return 0.0;
+        }
+
+        let rank = ((q * total as f64).ceil() as u64).max(1);
+        let mut cumulative = 0u64;
+        let mut lower_bound = 0.0;
+
+        for (i, &count) in self.counts.iter().enumerate() {
+            let upper_bound = LATENCY_BUCKET_BOUNDS_MS.get(i).copied();
+            if cumulative + count >= rank {
+                return match upper_bound {
+                    Some(upper) if count > 0 => {
+                        let frac = (rank - cumulative) as f64 / count as f64;
+                        lower_bound + frac * (upper - lower_bound)
+                    }
+                    // The overflow bucket (elapsed_ms > last bound) has no
+                    // upper bound to interpolate towards; report the true
+                    // observed max instead of silently capping at the last
+                    // finite bound.
+                    None => self.max_ms,
+                    Some(_) => lower_bound,
+                };
+            }
+            cumulative += count;
+            lower_bound = upper_bound.unwrap_or(lower_bound);
+        }
+
+        self.max_ms
+    }
+
+    fn percentiles(&self) -> LatencyPercentiles {
+        LatencyPercentiles {
+            p50: self.percentile(0.50),
+            p90: self.percentile(0.90),
+            p95: self.percentile(0.95),
+            p99: self.percentile(0.99),
+            max: self.max_ms,
+        }
+    }
+}
+
+fn build_prometheus_metrics(registry: &mut Registry) -> PrometheusMetrics {
+    let cpu_usage_percent = Gauge::<f64, AtomicU64>::default();
+    registry.register(
+        "cpu_usage_percent",
+        "Current host CPU usage percentage",
+        cpu_usage_percent.clone(),
+    );
+
+    let memory_used_bytes = Gauge::default();
+    registry.register(
+        "memory_used_bytes",
+        "Current host memory used, in bytes",
+        memory_used_bytes.clone(),
+    );
+
+    let memory_total_bytes = Gauge::default();
+    registry.register(
+        "memory_total_bytes",
+        "Total host memory, in bytes",
+        memory_total_bytes.clone(),
+    );
+
+    let memory_usage_percent = Gauge::<f64, AtomicU64>::default();
+    registry.register(
+        "memory_usage_percent",
+        "Current host memory usage percentage",
+        memory_usage_percent.clone(),
+    );
+
+    let uptime_seconds = Gauge::default();
+    registry.register(
+        "uptime_seconds",
+        "Host uptime in seconds",
+        uptime_seconds.clone(),
+    );
+
+    let total_requests = Counter::default();
+    registry.register(
+        "total_requests",
+        "Total number of proxied requests received",
+        total_requests.clone(),
+    );
+
+    let total_bytes_in = Counter::default();
+    registry.register(
+        "total_bytes_in",
+        "Total number of request bytes received",
+        total_bytes_in.clone(),
+    );
+
+    let total_bytes_out = Counter::default();
+    registry.register(
+        "total_bytes_out",
+        "Total number of response bytes sent",
+        total_bytes_out.clone(),
+    );
+
+    let responses_by_status = Family::<StatusLabels, Counter>::default();
+    registry.register(
+        "responses_by_status",
+        "Number of proxied responses by HTTP status code",
+        responses_by_status.clone(),
+    );
+
+    let requests_by_endpoint = Family::<EndpointLabels, Counter>::default();
+    registry.register(
+        "requests_by_endpoint",
+        "Number of proxied requests by endpoint path",
+        requests_by_endpoint.clone(),
+    );
+
+    let token_total_processed = Family::<ModelLabels, Counter>::default();
+    registry.register(
+        "token_total_processed",
+        "Total number of tokens processed by model",
+        token_total_processed.clone(),
+    );
+
+    let tokens_total = Family::<ModelDirectionLabels, Counter>::default();
+    registry.register(
+        "tokens_total",
+        "Total number of tokens processed by model and direction",
+        tokens_total.clone(),
+    );
+
+    PrometheusMetrics {
+        cpu_usage_percent,
+        memory_used_bytes,
+        memory_total_bytes,
+        memory_usage_percent,
+        uptime_seconds,
+        total_requests,
+        total_bytes_in,
+        total_bytes_out,
+        responses_by_status,
+        requests_by_endpoint,
+        token_total_processed,
+        tokens_total,
+    }
 }
 
 // System metrics structure
@@ -28,6 +1092,7 @@ struct SystemMetrics {
     uptime: u64,
     server_uptime: u64,
     timestamp: u64,
+    instance_id: String,
 }
 
 #[derive(Serialize)]
@@ -53,6 +1118,150 @@ struct TokenMetrics {
     output_tokens: u64,
     by_model: HashMap<String, u64>,
     timestamp: u64,
+    #[serde(skip)]
+    processing_stats: HashMap<String, TokenProcessingStats>,
+}
+
+// Number of linear steps per power-of-two octave in `LogLinearHistogram`.
+const LOG_LINEAR_SUBDIVISIONS: u32 = 4;
+
+// Smallest power-of-two magnitude `LogLinearHistogram` will distinguish.
+// Without a negative floor, every value in (0.0, 1.0] collapses into the
+// magnitude-0 octave regardless of how small it is (0.001 and 0.9 would be
+// indistinguishable), which defeats the "resolution at small values" this
+// histogram exists for. -24 resolves down to ~2^-24 (~6e-8), well past
+// sub-millisecond latencies and sub-1 tokens/sec throughput.
+const LOG_LINEAR_MIN_MAGNITUDE: i32 = -24;
+
+/// Log-linear histogram: buckets are grouped by power-of-two magnitude with
+/// `LOG_LINEAR_SUBDIVISIONS` linear steps within each magnitude. This keeps
+/// resolution high at small values (sub-millisecond latencies) without
+/// needing unboundedly many buckets for large ones. Mergeable/resettable so
+/// callers can combine histograms across servers or reset between windows.
+#[derive(Clone, Default)]
+struct LogLinearHistogram {
+    buckets: HashMap<u32, u64>,
+    count: u64,
+    sum: f64,
+    max: f64,
+}
+
+impl LogLinearHistogram {
+    fn bucket_id(value: f64) -> u32 {
+        if value <= 0.0 {
+            return 0;
+        }
+        let magnitude = (value.log2().floor() as i32).max(LOG_LINEAR_MIN_MAGNITUDE);
+        let lower = 2f64.powi(magnitude);
+        let upper = 2f64.powi(magnitude + 1);
+        let frac = ((value - lower) / (upper - lower)).clamp(0.0, 1.0);
+        let subdivision = ((frac * LOG_LINEAR_SUBDIVISIONS as f64) as u32).min(LOG_LINEAR_SUBDIVISIONS - 1);
+        (magnitude - LOG_LINEAR_MIN_MAGNITUDE) as u32 * LOG_LINEAR_SUBDIVISIONS + subdivision
+    }
+
+    fn bucket_bounds(bucket_id: u32) -> (f64, f64) {
+        let magnitude = (bucket_id / LOG_LINEAR_SUBDIVISIONS) as i32 + LOG_LINEAR_MIN_MAGNITUDE;
+        let subdivision = bucket_id % LOG_LINEAR_SUBDIVISIONS;
+        let lower_mag = 2f64.powi(magnitude);
+        let upper_mag = 2f64.powi(magnitude + 1);
+        let step = (upper_mag - lower_mag) / LOG_LINEAR_SUBDIVISIONS as f64;
+        (lower_mag + step * subdivision as f64, lower_mag + step * (subdivision as f64 + 1.0))
+    }
+
+    fn record(&mut self, value: f64) {
+        *self.buckets.entry(Self::bucket_id(value)).or_insert(0) += 1;
+        self.count += 1;
+        self.sum += value;
+        self.max = self.max.max(value);
+    }
+
+    fn merge(&mut self, other: &LogLinearHistogram) {
+        for (id, count) in &other.buckets {
+            *self.buckets.entry(*id).or_insert(0) += count;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+    }
+
+    fn reset(&mut self) {
+        self.buckets.clear();
+        self.count = 0;
+        self.sum = 0.0;
+        self.max = 0.0;
+    }
+
+    /// Walks cumulative bucket counts to find the bucket containing the
+    /// target rank, then linearly interpolates within it.
+    fn percentile(&self, q: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+
+        let rank = ((q * self.count as f64).ceil() as u64).max(1);
+        let mut ids: Vec<&u32> = self.buckets.keys().collect();
+        ids.sort();
+
+        let mut cumulative = 0u64;
+        for &id in ids {
+            let count = self.buckets[id];
+            if cumulative + count >= rank {
+                let (lower, upper) = Self::bucket_bounds(*id);
+                if count == 0 {
+                    return lower;
+                }
+                let frac = (rank - cumulative) as f64 / count as f64;
+                return lower + frac * (upper - lower);
+            }
+            cumulative += count;
+        }
+
+        self.max
+    }
+}
+
+#[derive(Clone, Default)]
+struct TokenProcessingStats {
+    latency_ms: LogLinearHistogram,
+    tokens_per_second: LogLinearHistogram,
+}
+
+#[derive(Serialize)]
+struct LogLinearHistogramView {
+    buckets: HashMap<u32, u64>,
+    count: u64,
+    p50: f64,
+    p90: f64,
+    p99: f64,
+    max: f64,
+}
+
+impl From<&LogLinearHistogram> for LogLinearHistogramView {
+    fn from(histogram: &LogLinearHistogram) -> Self {
+        LogLinearHistogramView {
+            buckets: histogram.buckets.clone(),
+            count: histogram.count,
+            p50: histogram.percentile(0.50),
+            p90: histogram.percentile(0.90),
+            p99: histogram.percentile(0.99),
+            max: histogram.max,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TokenProcessingStatsView {
+    latency_ms: LogLinearHistogramView,
+    tokens_per_second: LogLinearHistogramView,
+}
+
+impl From<&TokenProcessingStats> for TokenProcessingStatsView {
+    fn from(stats: &TokenProcessingStats) -> Self {
+        TokenProcessingStatsView {
+            latency_ms: (&stats.latency_ms).into(),
+            tokens_per_second: (&stats.tokens_per_second).into(),
+        }
+    }
 }
 
 // New traffic metrics structure
@@ -61,24 +1270,28 @@ struct TrafficMetrics {
     // Request counts
     total_requests: u64,
     requests_by_endpoint: HashMap<String, u64>,
-    
+
     // Response status counts
     responses_by_status: HashMap<u16, u64>,
-    
+
     // Data transfer
     total_bytes_in: u64,
     total_bytes_out: u64,
-    
+
     // Timing statistics
     avg_response_time_ms: f64,
-    response_times: HashMap<String, Vec<f64>>, // Path to list of response times
-    
+    #[serde(skip)]
+    response_time_histograms: HashMap<String, LatencyHistogram>,
+
     // User-agent statistics
     requests_by_user_agent: HashMap<String, u64>,
-    
+
     // Hourly request counts for the last 24 hours
     hourly_requests: [u64; 24],
-    
+
+    // Requests rejected by the per-key token-bucket rate limiter
+    rate_limited_requests: u64,
+
     // Last update timestamp
     last_updated: u64,
 }
@@ -91,32 +1304,43 @@ async fn proxy_handler(
     state: web::Data<Arc<AppState>>,
 ) -> HttpResponse {
     let start_time = Instant::now();
-    
+
     // Get path and query string
     let path = req.uri().path();
     let query_string = req.uri().query().map_or_else(String::new, |q| format!("?{}", q));
-    
+
+    // Enforce the per-key token-bucket rate limit before doing any other work
+    let key = rate_limit_key(&req);
+    if let Err(retry_after) = state.rate_limiter.check(&key) {
+        let mut traffic_metrics = state.traffic_metrics.write().unwrap();
+        traffic_metrics.rate_limited_requests += 1;
+
+        return HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", format!("{:.0}", retry_after.ceil())))
+            .body("Rate limit exceeded");
+    }
+
     // Build target URL
     let target_url = format!("{}{}{}", BACKEND_URL, path, query_string);
     println!("🔄 Proxying request to {}", target_url);
-    
+
     // Extract method
     let method = req.method().clone();
-    
+
     // Track incoming request in metrics
     {
         let mut traffic_metrics = state.traffic_metrics.write().unwrap();
         traffic_metrics.total_requests += 1;
-        
+
         // Update endpoint metrics
         let endpoint_counter = traffic_metrics.requests_by_endpoint
             .entry(path.to_string())
             .or_insert(0);
         *endpoint_counter += 1;
-        
+
         // Track request size
         traffic_metrics.total_bytes_in += body.len() as u64;
-        
+
         // Track user agent
         if let Some(user_agent) = req.headers().get(header::USER_AGENT) {
             if let Ok(ua_str) = user_agent.to_str() {
@@ -126,18 +1350,31 @@ async fn proxy_handler(
                 *ua_counter += 1;
             }
         }
-        
+
         // Update hourly metrics - Fix for hour() method
         let current_hour = chrono::Local::now().hour() as usize;
         traffic_metrics.hourly_requests[current_hour] += 1;
-        
+
         // Update timestamp
         traffic_metrics.last_updated = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or(Duration::from_secs(0))
             .as_secs();
+
+        // Mirror into the Prometheus registry
+        state.prom.total_requests.inc();
+        state.prom.total_bytes_in.inc_by(body.len() as u64);
+        state.prom.requests_by_endpoint
+            .get_or_create(&EndpointLabels { path: path.to_string() })
+            .inc();
     }
 
+    // Run the request through the filter pipeline before it leaves for upstream
+    let body = match run_request_filters(&state.filters, &req, body).await {
+        Ok(body) => body,
+        Err(short_circuit) => return short_circuit,
+    };
+
     // Create a new awc request with the same method
     let mut forwarded_req = match method.as_str() {
         "GET" => client.get(target_url),
@@ -152,12 +1389,19 @@ async fn proxy_handler(
             return HttpResponse::MethodNotAllowed().finish();
         }
     };
-    
+
     // Forward the headers
     for (header_name, header_value) in req.headers().iter().filter(|(h, _)| *h != header::HOST) {
         forwarded_req = forwarded_req.insert_header((header_name.clone(), header_value.clone()));
     }
-    
+
+    // Let filters inject their own headers (e.g. upstream auth) last, so they win ties
+    for filter in &state.filters {
+        for (name, value) in filter.request_headers_to_inject() {
+            forwarded_req = forwarded_req.insert_header((name, value));
+        }
+    }
+
     // Send the request with the body - FIX: make response mutable
     let mut response = match forwarded_req.send_body(body).await {
         Ok(response) => response,
@@ -166,17 +1410,17 @@ async fn proxy_handler(
             return HttpResponse::InternalServerError().body(format!("Proxy error: {}", err));
         }
     };
-    
+
     let status = response.status();
     let mut client_resp = HttpResponse::build(status);
-    
+
     // Copy the response headers
     for (header_name, header_value) in response.headers().iter() {
         if header_name != header::CONTENT_LENGTH {
             client_resp.insert_header((header_name.clone(), header_value.clone()));
         }
     }
-    
+
     // Get response body - now with mutable response
     let body_bytes = match response.body().await {
         Ok(bytes) => bytes,
@@ -185,101 +1429,199 @@ async fn proxy_handler(
             return HttpResponse::InternalServerError().body(format!("Error reading response: {}", err));
         }
     };
-    
+
+    // Run the response through the filter pipeline before it reaches the client
+    let body_bytes = run_response_filters(&state.filters, status, response.headers(), body_bytes).await;
+
     // Track response in metrics
     {
         let mut traffic_metrics = state.traffic_metrics.write().unwrap();
-        
+
         // Update status code metrics
         let status_counter = traffic_metrics.responses_by_status
             .entry(status.as_u16())
             .or_insert(0);
         *status_counter += 1;
-        
+
         // Track response size
         traffic_metrics.total_bytes_out += body_bytes.len() as u64;
-        
+
         // Track response time
         let elapsed = start_time.elapsed().as_secs_f64() * 1000.0; // ms
-        
+
         // Update average response time (weighted moving average)
         if traffic_metrics.total_requests > 1 {
-            traffic_metrics.avg_response_time_ms = 
-                (traffic_metrics.avg_response_time_ms * (traffic_metrics.total_requests as f64 - 1.0) 
+            traffic_metrics.avg_response_time_ms =
+                (traffic_metrics.avg_response_time_ms * (traffic_metrics.total_requests as f64 - 1.0)
                  + elapsed) / traffic_metrics.total_requests as f64;
         } else {
             traffic_metrics.avg_response_time_ms = elapsed;
         }
-        
-        // Store response time by path
-        traffic_metrics.response_times
+
+        // Store response time in the path's latency histogram
+        traffic_metrics.response_time_histograms
             .entry(path.to_string())
-            .or_insert_with(Vec::new)
-            .push(elapsed);
-        
-        // Limit the number of stored times to prevent memory bloat
-        if let Some(times) = traffic_metrics.response_times.get_mut(path) {
-            if times.len() > 100 {
-                // Keep only the latest 100 entries
-                *times = times.iter().skip(times.len() - 100).cloned().collect();
-            }
+            .or_default()
+            .record(elapsed);
+
+        // Mirror into the Prometheus registry
+        state.prom.total_bytes_out.inc_by(body_bytes.len() as u64);
+        state.prom.responses_by_status
+            .get_or_create(&StatusLabels { status: status.as_u16().to_string() })
+            .inc();
+
+        // Push to StatsD, if configured
+        if let Some(statsd) = &state.statsd {
+            let status_str = status.as_u16().to_string();
+            let _ = statsd.count_with_tags("requests", 1)
+                .with_tag("endpoint", path)
+                .with_tag("status", &status_str)
+                .try_send();
+            let _ = statsd.time_with_tags("response_time_ms", elapsed as u64)
+                .with_tag("path", path)
+                .try_send();
+            let _ = statsd.count("bytes_out", body_bytes.len() as i64).try_send();
         }
     }
-    
+
     // Return the response
     client_resp.body(body_bytes)
 }
 
+#[derive(Serialize)]
+struct TrafficMetricsResponse {
+    #[serde(flatten)]
+    traffic: TrafficMetrics,
+    latency_percentiles: HashMap<String, LatencyPercentiles>,
+}
+
 // Traffic metrics endpoint
 async fn traffic_metrics(state: web::Data<Arc<AppState>>) -> impl Responder {
     let traffic_data = state.traffic_metrics.read().unwrap().clone();
-    HttpResponse::Ok().json(traffic_data)
+
+    let latency_percentiles = traffic_data.response_time_histograms
+        .iter()
+        .map(|(path, histogram)| (path.clone(), histogram.percentiles()))
+        .collect();
+
+    HttpResponse::Ok().json(TrafficMetricsResponse {
+        traffic: traffic_data,
+        latency_percentiles,
+    })
 }
 
 // Original metrics endpoints
 async fn metrics(data: web::Data<Arc<AppState>>) -> impl Responder {
     let mut system = data.system.lock().unwrap();
     system.refresh_all();
-    
-    let metrics = build_system_metrics(&system, &data.start_time);
+
+    let metrics = build_system_metrics(&system, &data.start_time, &data.instance_id);
     HttpResponse::Ok().json(metrics)
 }
 
 async fn system_metrics(data: web::Data<Arc<AppState>>) -> impl Responder {
     let mut system = data.system.lock().unwrap();
     system.refresh_all();
-    
-    let metrics = build_system_metrics(&system, &data.start_time);
+
+    let metrics = build_system_metrics(&system, &data.start_time, &data.instance_id);
     HttpResponse::Ok().json(metrics)
 }
 
+#[derive(Serialize)]
+struct TokenMetricsResponse {
+    #[serde(flatten)]
+    tokens: TokenMetrics,
+    processing_stats: HashMap<String, TokenProcessingStatsView>,
+}
+
 async fn token_metrics(data: web::Data<Arc<AppState>>) -> impl Responder {
     let token_data = data.token_metrics.read().unwrap().clone();
-    HttpResponse::Ok().json(token_data)
+
+    let processing_stats = token_data.processing_stats
+        .iter()
+        .map(|(model, stats)| (model.clone(), stats.into()))
+        .collect();
+
+    HttpResponse::Ok().json(TokenMetricsResponse {
+        tokens: token_data,
+        processing_stats,
+    })
 }
 
-// Update token metrics endpoint
-async fn update_token_metrics(
-    data: web::Data<Arc<AppState>>, 
-    payload: web::Json<TokenUsageRequest>
-) -> impl Responder {
-    let mut token_data = data.token_metrics.write().unwrap();
-    
+/// Applies a token-usage observation to the shared metrics state: the
+/// running totals, the Prometheus/StatsD counters, the per-model latency and
+/// throughput histograms, the SSE broadcast, and (async) alert evaluation.
+/// This is the single source of truth for "a batch of tokens was processed" —
+/// both the manual `/metrics/tokens` endpoint and the proxy's automatic
+/// `TokenCountingFilter` funnel through it so the two never diverge.
+async fn apply_token_usage(state: &Arc<AppState>, usage: &TokenUsageRequest) {
+    let mut token_data = state.token_metrics.write().unwrap();
+
     // Update token metrics
-    token_data.total_processed += payload.total;
-    token_data.input_tokens += payload.input.unwrap_or(0);
-    token_data.output_tokens += payload.output.unwrap_or(0);
+    token_data.total_processed += usage.total;
+    token_data.input_tokens += usage.input.unwrap_or(0);
+    token_data.output_tokens += usage.output.unwrap_or(0);
     token_data.timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or(Duration::from_secs(0))
         .as_secs();
-    
+
     // Update per-model metrics
-    if let Some(model) = &payload.model {
+    if let Some(model) = &usage.model {
         let entry = token_data.by_model.entry(model.clone()).or_insert(0);
-        *entry += payload.total;
+        *entry += usage.total;
+
+        state.prom.token_total_processed
+            .get_or_create(&ModelLabels { model: model.clone() })
+            .inc_by(usage.total);
+
+        if let Some(input) = usage.input {
+            state.prom.tokens_total
+                .get_or_create(&ModelDirectionLabels { model: model.clone(), direction: "input".to_string() })
+                .inc_by(input);
+        }
+        if let Some(output) = usage.output {
+            state.prom.tokens_total
+                .get_or_create(&ModelDirectionLabels { model: model.clone(), direction: "output".to_string() })
+                .inc_by(output);
+        }
+
+        if let Some(statsd) = &state.statsd {
+            let _ = statsd.count_with_tags("tokens", usage.total as i64)
+                .with_tag("model", model)
+                .try_send();
+        }
+
+        let stats = token_data.processing_stats.entry(model.clone()).or_default();
+        if let Some(latency_ms) = usage.latency_ms {
+            stats.latency_ms.record(latency_ms);
+        }
+        if let Some(duration_ms) = usage.duration_ms {
+            if duration_ms > 0.0 {
+                stats.tokens_per_second.record(usage.total as f64 / (duration_ms / 1000.0));
+            }
+        }
+
+        let total_for_model = *entry;
+        let state = Arc::clone(state);
+        let model = model.clone();
+        actix_web::rt::spawn(async move {
+            evaluate_token_alerts(&state, &model, total_for_model).await;
+        });
+    }
+
+    if let Ok(json) = serde_json::to_string(&*token_data) {
+        let _ = state.metrics_stream.send(json);
     }
-    
+}
+
+// Update token metrics endpoint
+async fn update_token_metrics(
+    data: web::Data<Arc<AppState>>,
+    payload: web::Json<TokenUsageRequest>
+) -> impl Responder {
+    apply_token_usage(&data, &payload).await;
+
     HttpResponse::Ok().json(serde_json::json!({
         "success": true,
         "message": "Token metrics updated successfully"
@@ -293,34 +1635,619 @@ struct TokenUsageRequest {
     total: u64,
     input: Option<u64>,
     output: Option<u64>,
+    // How long this batch took to process, used to update the per-model
+    // latency/throughput histograms below.
+    latency_ms: Option<f64>,
+    duration_ms: Option<f64>,
+}
+
+// Renders the current system/traffic/token metrics in the Prometheus text
+// exposition format so the server can be scraped directly.
+async fn prometheus_metrics_handler(data: web::Data<Arc<AppState>>) -> impl Responder {
+    {
+        let mut system = data.system.lock().unwrap();
+        system.refresh_all();
+        let sys = build_system_metrics(&system, &data.start_time, &data.instance_id);
+
+        data.prom.cpu_usage_percent.set(sys.cpu.usage as f64);
+        data.prom.memory_used_bytes.set(sys.memory.used as i64);
+        data.prom.memory_total_bytes.set(sys.memory.total as i64);
+        data.prom.memory_usage_percent.set(sys.memory.usage_percent as f64);
+        data.prom.uptime_seconds.set(sys.uptime as i64);
+    }
+
+    let mut buffer = String::new();
+    if let Err(err) = encode(&mut buffer, &data.registry.lock().unwrap()) {
+        println!("❌ Error encoding Prometheus metrics: {}", err);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(buffer)
+}
+
+// Reports process/instance identity so dashboards can tell restarts and
+// replicas apart without relying on clocks alone.
+async fn instance_info(data: web::Data<Arc<AppState>>) -> impl Responder {
+    HttpResponse::Ok().json(InstanceInfo {
+        instance_id: data.instance_id.clone(),
+        startup_utc: data.startup_utc,
+        machine_id: data.machine_id.clone(),
+        crate_version: env!("CARGO_PKG_VERSION"),
+        git_version: option_env!("GIT_COMMIT_HASH").unwrap_or("unknown"),
+    })
+}
+
+/// Outcome of a filter's request/response hook: let the request/response
+/// through unchanged, swap in a new body, or (request-side only in
+/// practice) short-circuit with a response of the filter's choosing.
+enum FilterAction {
+    Continue,
+    Rewrite(web::Bytes),
+    ShortCircuit(HttpResponse),
+}
+
+/// A middleware stage in the proxy path, run in registration order for both
+/// the forwarded request and the upstream response. Mirrors Pingora's
+/// request/response body filter hooks.
+#[async_trait]
+trait ProxyFilter: Send + Sync {
+    async fn on_request(&self, req: &HttpRequest, body: web::Bytes) -> FilterAction;
+    async fn on_response(&self, status: StatusCode, headers: &HeaderMap, body: web::Bytes) -> web::Bytes;
+
+    /// Headers to add to the forwarded request. Most filters don't need
+    /// this, so it defaults to none.
+    fn request_headers_to_inject(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+}
+
+/// Injects a fixed set of headers (e.g. an upstream auth token) onto every
+/// forwarded request.
+struct HeaderInjectionFilter {
+    headers: Vec<(String, String)>,
+}
+
+#[async_trait]
+impl ProxyFilter for HeaderInjectionFilter {
+    async fn on_request(&self, _req: &HttpRequest, _body: web::Bytes) -> FilterAction {
+        FilterAction::Continue
+    }
+
+    async fn on_response(&self, _status: StatusCode, _headers: &HeaderMap, body: web::Bytes) -> web::Bytes {
+        body
+    }
+
+    fn request_headers_to_inject(&self) -> Vec<(String, String)> {
+        self.headers.clone()
+    }
+}
+
+/// Redacts configured secret field names from JSON response bodies before
+/// they reach the client (e.g. upstream API keys echoed back in an error).
+struct JsonRedactionFilter {
+    secret_fields: Vec<String>,
+}
+
+impl JsonRedactionFilter {
+    fn redact(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, val) in map.iter_mut() {
+                    if self.secret_fields.iter().any(|f| f.eq_ignore_ascii_case(key)) {
+                        *val = serde_json::Value::String("[REDACTED]".to_string());
+                    } else {
+                        self.redact(val);
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for item in items.iter_mut() {
+                    self.redact(item);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[async_trait]
+impl ProxyFilter for JsonRedactionFilter {
+    async fn on_request(&self, _req: &HttpRequest, body: web::Bytes) -> FilterAction {
+        FilterAction::Rewrite(body)
+    }
+
+    async fn on_response(&self, _status: StatusCode, _headers: &HeaderMap, body: web::Bytes) -> web::Bytes {
+        let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(&body) else {
+            return body;
+        };
+
+        self.redact(&mut value);
+
+        match serde_json::to_vec(&value) {
+            Ok(redacted) => web::Bytes::from(redacted),
+            Err(_) => body,
+        }
+    }
+}
+
+/// Parses LLM-style `usage` blocks out of proxied responses and feeds them
+/// into the same token metrics the `/metrics/tokens` endpoint updates
+/// manually, so token accounting happens automatically for any upstream
+/// that reports usage this way.
+// Holds the shared `AppState` as a `Weak` rather than a direct field because
+// `AppState` itself owns the filter chain (`AppState::filters`), so the
+// `Arc<AppState>` only exists once this filter has already been constructed.
+// `main` fills in the reference with `Arc::downgrade(&state)` right after
+// `state` is built.
+struct TokenCountingFilter {
+    state: OnceLock<Weak<AppState>>,
+}
+
+#[async_trait]
+impl ProxyFilter for TokenCountingFilter {
+    async fn on_request(&self, _req: &HttpRequest, body: web::Bytes) -> FilterAction {
+        FilterAction::Rewrite(body)
+    }
+
+    async fn on_response(&self, _status: StatusCode, _headers: &HeaderMap, body: web::Bytes) -> web::Bytes {
+        let Some(state) = self.state.get().and_then(Weak::upgrade) else {
+            return body;
+        };
+
+        if let Ok(value) = serde_json::from_slice::<serde_json::Value>(&body) {
+            let usage = value.get("usage");
+            let total = usage
+                .and_then(|u| u.get("total_tokens"))
+                .and_then(|v| v.as_u64());
+
+            if let Some(total) = total {
+                let input = usage.and_then(|u| u.get("prompt_tokens")).and_then(|v| v.as_u64());
+                let output = usage.and_then(|u| u.get("completion_tokens")).and_then(|v| v.as_u64());
+                let model = value.get("model").and_then(|v| v.as_str()).map(|s| s.to_string());
+
+                // Route through the same update path as the manual
+                // `/metrics/tokens` endpoint so auto-detected usage shows up
+                // in the SSE stream, Prometheus/StatsD counters, the
+                // per-model histograms, and alert evaluation too.
+                let usage = TokenUsageRequest {
+                    model,
+                    total,
+                    input,
+                    output,
+                    latency_ms: None,
+                    duration_ms: None,
+                };
+                apply_token_usage(&state, &usage).await;
+            }
+        }
+
+        body
+    }
+}
+
+/// Runs every registered filter's `on_request` hook in order, threading the
+/// (possibly rewritten) body through. Returns `Err` with the short-circuit
+/// response as soon as one fires.
+async fn run_request_filters(
+    filters: &[Arc<dyn ProxyFilter>],
+    req: &HttpRequest,
+    mut body: web::Bytes,
+) -> Result<web::Bytes, HttpResponse> {
+    for filter in filters {
+        match filter.on_request(req, body.clone()).await {
+            FilterAction::Continue => {}
+            FilterAction::Rewrite(new_body) => body = new_body,
+            FilterAction::ShortCircuit(resp) => return Err(resp),
+        }
+    }
+    Ok(body)
+}
+
+/// Runs every registered filter's `on_response` hook in order.
+async fn run_response_filters(
+    filters: &[Arc<dyn ProxyFilter>],
+    status: StatusCode,
+    headers: &HeaderMap,
+    mut body: web::Bytes,
+) -> web::Bytes {
+    for filter in filters {
+        body = filter.on_response(status, headers, body).await;
+    }
+    body
+}
+
+/// The metric an alert rule watches and the threshold it fires past.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+enum AlertCondition {
+    /// Fires once `total_processed` for `model` exceeds `threshold` tokens.
+    TokenTotalExceeds { model: String, threshold: u64 },
+    /// Fires once host memory usage has stayed above `percent` for at
+    /// least `sustained_secs` seconds.
+    MemoryUsageAbove { percent: f32, sustained_secs: u64 },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct AlertRule {
+    id: String,
+    name: String,
+    condition: AlertCondition,
+    webhook_url: String,
+    // Minimum time between repeated firings of this rule, so a condition
+    // that stays true doesn't spam the webhook.
+    debounce_secs: u64,
+}
+
+#[derive(Deserialize)]
+struct CreateAlertRuleRequest {
+    name: String,
+    condition: AlertCondition,
+    webhook_url: String,
+    debounce_secs: u64,
+}
+
+/// Rejects `webhook_url`s that would let `maybe_fire_alert` be used as an
+/// SSRF proxy: anything other than plain `http(s)`, and any host that
+/// resolves (as a literal) to loopback, private, link-local, or unspecified
+/// space — which also covers the `169.254.169.254` cloud metadata endpoint.
+/// DNS names are let through as-is; blocking those against rebinding would
+/// require a resolve-time check, not a string check on the rule body.
+fn validate_webhook_url(raw: &str) -> Result<(), &'static str> {
+    let rest = raw
+        .strip_prefix("https://")
+        .or_else(|| raw.strip_prefix("http://"))
+        .ok_or("webhook_url must use the http or https scheme")?;
+
+    let host_port = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host_port = host_port.rsplit('@').next().unwrap_or(host_port); // drop userinfo, if any
+    if host_port.is_empty() {
+        return Err("webhook_url is missing a host");
+    }
+
+    let host = match host_port.strip_prefix('[') {
+        Some(after_bracket) => after_bracket.split(']').next().unwrap_or(""), // "[::1]:8080"
+        None => host_port.split(':').next().unwrap_or(host_port),
+    };
+    let host = host.to_ascii_lowercase();
+    if host.is_empty() {
+        return Err("webhook_url is missing a host");
+    }
+    if host == "localhost" || host.ends_with(".localhost") {
+        return Err("webhook_url must not target localhost");
+    }
+
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        if is_blocked_ip(&ip) {
+            return Err("webhook_url must not target a loopback, private, or link-local address");
+        }
+    }
+
+    Ok(())
+}
+
+fn is_blocked_ipv4(v4: std::net::Ipv4Addr) -> bool {
+    v4.is_loopback()
+        || v4.is_private()
+        || v4.is_link_local() // includes 169.254.169.254
+        || v4.is_unspecified()
+        || v4.is_broadcast()
+}
+
+fn is_blocked_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_blocked_ipv4(*v4),
+        IpAddr::V6(v6) => {
+            // IPv4-mapped (`::ffff:a.b.c.d`) addresses aren't caught by any
+            // of std's IPv6 `is_*` predicates, so unwrap and re-run the IPv4
+            // checks against the real address before falling back to the
+            // v6-specific ranges.
+            if let Some(mapped) = v6.to_ipv4_mapped() {
+                return is_blocked_ipv4(mapped);
+            }
+
+            let leading = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || (leading & 0xfe00) == 0xfc00 // unique-local fc00::/7
+                || (leading & 0xffc0) == 0xfe80 // link-local fe80::/10
+        }
+    }
+}
+
+// Per-rule state that isn't persisted: when it last fired (for debouncing)
+// and, for "sustained" conditions, when the condition first became true.
+#[derive(Default)]
+struct AlertRuntimeState {
+    last_fired: Option<Instant>,
+    breached_since: Option<Instant>,
+}
+
+#[derive(Serialize)]
+struct AlertWebhookPayload<'a> {
+    rule_name: &'a str,
+    current_value: f64,
+    threshold: f64,
+    timestamp: u64,
+}
+
+fn load_alert_rules(conn: &Connection) -> rusqlite::Result<HashMap<String, AlertRule>> {
+    let mut stmt = conn.prepare("SELECT id, name, condition, webhook_url, debounce_secs FROM alert_rules")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, i64>(4)? as u64,
+        ))
+    })?;
+
+    let mut rules = HashMap::new();
+    for row in rows.flatten() {
+        let (id, name, condition_json, webhook_url, debounce_secs) = row;
+        if let Ok(condition) = serde_json::from_str::<AlertCondition>(&condition_json) {
+            rules.insert(id.clone(), AlertRule { id, name, condition, webhook_url, debounce_secs });
+        }
+    }
+    Ok(rules)
+}
+
+fn persist_alert_rule(conn: &Connection, rule: &AlertRule) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO alert_rules (id, name, condition, webhook_url, debounce_secs)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![
+            rule.id,
+            rule.name,
+            serde_json::to_string(&rule.condition).unwrap_or_default(),
+            rule.webhook_url,
+            rule.debounce_secs as i64,
+        ],
+    )?;
+    Ok(())
+}
+
+fn delete_alert_rule(conn: &Connection, id: &str) -> rusqlite::Result<usize> {
+    conn.execute("DELETE FROM alert_rules WHERE id = ?1", rusqlite::params![id])
+}
+
+/// Fires a rule's webhook if `current_value` breaches its threshold and the
+/// rule isn't still within its debounce window.
+async fn maybe_fire_alert(rule: &AlertRule, runtime: &Mutex<HashMap<String, AlertRuntimeState>>, current_value: f64, threshold: f64) {
+    {
+        let mut runtime = runtime.lock().unwrap();
+        let state = runtime.entry(rule.id.clone()).or_default();
+        if let Some(last_fired) = state.last_fired {
+            if last_fired.elapsed() < Duration::from_secs(rule.debounce_secs) {
+                return;
+            }
+        }
+        state.last_fired = Some(Instant::now());
+    }
+
+    let payload = AlertWebhookPayload {
+        rule_name: &rule.name,
+        current_value,
+        threshold,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+            .as_secs(),
+    };
+
+    let client = awc::Client::default();
+    if let Err(err) = client.post(&rule.webhook_url).send_json(&payload).await {
+        println!("❌ Failed to deliver alert webhook for '{}': {}", rule.name, err);
+    }
+}
+
+/// Checks every `TokenTotalExceeds` rule against the just-updated token
+/// totals. Called from `apply_token_usage` so token alerts fire as soon
+/// as the threshold is crossed, not on the next polling interval.
+async fn evaluate_token_alerts(state: &Arc<AppState>, model: &str, total_for_model: u64) {
+    let rules: Vec<AlertRule> = state.alert_rules.read().unwrap().values().cloned().collect();
+    for rule in rules {
+        if let AlertCondition::TokenTotalExceeds { model: rule_model, threshold } = &rule.condition {
+            if rule_model == model && total_for_model > *threshold {
+                maybe_fire_alert(&rule, &state.alert_runtime, total_for_model as f64, *threshold as f64).await;
+            }
+        }
+    }
+}
+
+/// Checks every `MemoryUsageAbove` rule against the current memory usage
+/// percentage, tracking how long the breach has been sustained.
+async fn evaluate_memory_alerts(state: &Arc<AppState>, usage_percent: f32) {
+    let rules: Vec<AlertRule> = state.alert_rules.read().unwrap().values().cloned().collect();
+    for rule in rules {
+        if let AlertCondition::MemoryUsageAbove { percent, sustained_secs } = &rule.condition {
+            let mut runtime = state.alert_runtime.lock().unwrap();
+            let runtime_state = runtime.entry(rule.id.clone()).or_default();
+
+            if usage_percent > *percent {
+                let since = *runtime_state.breached_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= Duration::from_secs(*sustained_secs) {
+                    drop(runtime);
+                    maybe_fire_alert(&rule, &state.alert_runtime, usage_percent as f64, *percent as f64).await;
+                }
+            } else {
+                runtime_state.breached_since = None;
+            }
+        }
+    }
+}
+
+/// Periodically re-checks sustained conditions (currently just
+/// `MemoryUsageAbove`) that can't be evaluated from a single request.
+async fn alert_task(state: Arc<AppState>) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(15));
+    loop {
+        interval.tick().await;
+        let usage_percent = {
+            let mut system = state.system.lock().unwrap();
+            system.refresh_memory();
+            if system.total_memory() > 0 {
+                (system.used_memory() as f32 / system.total_memory() as f32) * 100.0
+            } else {
+                0.0
+            }
+        };
+        evaluate_memory_alerts(&state, usage_percent).await;
+    }
+}
+
+// How often the rate limiter sweeps out buckets for keys that have gone
+// quiet, and how long a bucket can sit idle before it's swept.
+const RATE_LIMIT_SWEEP_INTERVAL_SECS: u64 = 300;
+const RATE_LIMIT_BUCKET_IDLE_SECS: u64 = 900;
+
+/// Bounds `RateLimiter`'s memory: without this, one bucket is created per
+/// distinct API key/IP ever seen and never removed.
+async fn rate_limiter_sweep_task(state: Arc<AppState>) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(RATE_LIMIT_SWEEP_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        state.rate_limiter.sweep_stale(Duration::from_secs(RATE_LIMIT_BUCKET_IDLE_SECS));
+    }
+}
+
+async fn list_alerts(state: web::Data<Arc<AppState>>) -> impl Responder {
+    let rules: Vec<AlertRule> = state.alert_rules.read().unwrap().values().cloned().collect();
+    HttpResponse::Ok().json(rules)
+}
+
+async fn create_alert(state: web::Data<Arc<AppState>>, payload: web::Json<CreateAlertRuleRequest>) -> impl Responder {
+    if let Err(reason) = validate_webhook_url(&payload.webhook_url) {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": reason }));
+    }
+
+    let rule = AlertRule {
+        id: Ulid::new().to_string(),
+        name: payload.name.clone(),
+        condition: payload.condition.clone(),
+        webhook_url: payload.webhook_url.clone(),
+        debounce_secs: payload.debounce_secs,
+    };
+
+    {
+        let conn = state.metrics_db.lock().unwrap();
+        if let Err(err) = persist_alert_rule(&conn, &rule) {
+            println!("❌ Failed to persist alert rule '{}': {}", rule.name, err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    state.alert_rules.write().unwrap().insert(rule.id.clone(), rule.clone());
+    HttpResponse::Ok().json(rule)
+}
+
+async fn delete_alert(state: web::Data<Arc<AppState>>, path: web::Path<String>) -> impl Responder {
+    let id = path.into_inner();
+
+    {
+        let conn = state.metrics_db.lock().unwrap();
+        if let Err(err) = delete_alert_rule(&conn, &id) {
+            println!("❌ Failed to delete alert rule '{}': {}", id, err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    state.alert_rules.write().unwrap().remove(&id);
+    state.alert_runtime.lock().unwrap().remove(&id);
+    HttpResponse::Ok().json(serde_json::json!({ "success": true }))
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     // Initialize shared state
+    let mut registry = Registry::default();
+    let prom = build_prometheus_metrics(&mut registry);
+
+    let token_metrics = Arc::new(RwLock::new(TokenMetrics {
+        total_processed: 0,
+        input_tokens: 0,
+        output_tokens: 0,
+        by_model: HashMap::new(),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+            .as_secs(),
+        processing_stats: HashMap::new(),
+    }));
+
+    let token_counting_filter = Arc::new(TokenCountingFilter { state: OnceLock::new() });
+
+    let filters: Vec<Arc<dyn ProxyFilter>> = vec![
+        Arc::new(HeaderInjectionFilter { headers: Vec::new() }),
+        Arc::new(JsonRedactionFilter {
+            secret_fields: vec![
+                "password".to_string(),
+                "api_key".to_string(),
+                "apikey".to_string(),
+                "token".to_string(),
+                "secret".to_string(),
+            ],
+        }),
+        token_counting_filter.clone(),
+    ];
+
+    let metrics_db = open_metrics_db().expect("failed to open metrics history database");
+    let alert_rules = load_alert_rules(&metrics_db).unwrap_or_else(|err| {
+        println!("❌ Failed to load persisted alert rules: {}", err);
+        HashMap::new()
+    });
+
     let state = Arc::new(AppState {
         system: Mutex::new(System::new_all()),
-        token_metrics: RwLock::new(TokenMetrics {
-            total_processed: 0,
-            input_tokens: 0,
-            output_tokens: 0,
-            by_model: HashMap::new(),
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or(Duration::from_secs(0))
-                .as_secs(),
-        }),
+        token_metrics,
         traffic_metrics: RwLock::new(TrafficMetrics::default()),
         start_time: SystemTime::now(),
+        registry: Mutex::new(registry),
+        prom,
+        rate_limiter: RateLimiter::new(),
+        metrics_db: Mutex::new(metrics_db),
+        filters,
+        statsd: build_statsd_client(),
+        instance_id: Ulid::new().to_string(),
+        startup_utc: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+            .as_secs(),
+        machine_id: read_machine_id(),
+        metrics_stream: broadcast::channel(METRICS_STREAM_CHANNEL_CAPACITY).0,
+        endpoint_stats: Mutex::new(HashMap::new()),
+        prev_network_counters: Mutex::new(HashMap::new()),
+        prev_network_refresh: Mutex::new(Instant::now()),
+        alert_rules: RwLock::new(alert_rules),
+        alert_runtime: Mutex::new(HashMap::new()),
     });
-    
+
+    // Wire the filter's back-reference now that `state` (which owns the
+    // filter chain) exists.
+    let _ = token_counting_filter.state.set(Arc::downgrade(&state));
+
     println!("🦀 Metrics & API Proxy server starting on http://localhost:3005");
-    
+
+    // Flush periodic rollups to SQLite so historical dashboards survive restarts
+    actix_web::rt::spawn(rollup_task(Arc::clone(&state)));
+
+    // Publish periodic frames for /metrics/stream subscribers
+    actix_web::rt::spawn(metrics_stream_task(Arc::clone(&state)));
+
+    // Re-check sustained alert conditions (e.g. memory usage) on a timer
+    actix_web::rt::spawn(alert_task(Arc::clone(&state)));
+
+    // Keep the rate limiter's bucket map from growing without bound
+    actix_web::rt::spawn(rate_limiter_sweep_task(Arc::clone(&state)));
+
     // Start HTTP server
     HttpServer::new(move || {
         // Create HTTP client for proxying requests
         let client = Client::default();
-        
+
         App::new()
             .app_data(web::Data::new(client.clone()))
             .app_data(web::Data::new(Arc::clone(&state)))
@@ -330,13 +2257,23 @@ async fn main() -> std::io::Result<()> {
                     .allow_any_method()
                     .allow_any_header()
             )
+            .wrap(HttpMetricsMiddleware { state: Arc::clone(&state) })
             // Dedicated metrics routes
             .service(web::resource("/metrics").route(web::get().to(metrics)))
             .service(web::resource("/metrics/system").route(web::get().to(system_metrics)))
             .service(web::resource("/metrics/tokens").route(web::get().to(token_metrics)))
             .service(web::resource("/metrics/tokens").route(web::post().to(update_token_metrics)))
             .service(web::resource("/metrics/traffic").route(web::get().to(traffic_metrics)))
-            
+            .service(web::resource("/metrics/prometheus").route(web::get().to(prometheus_metrics_handler)))
+            .service(web::resource("/metrics/history").route(web::get().to(metrics_history)))
+            .service(web::resource("/metrics/tokens/history").route(web::get().to(metrics_tokens_history)))
+            .service(web::resource("/metrics/info").route(web::get().to(instance_info)))
+            .service(web::resource("/metrics/stream").route(web::get().to(metrics_stream_handler)))
+            .service(web::resource("/metrics/http").route(web::get().to(http_metrics)))
+            .service(web::resource("/metrics/host").route(web::get().to(host_metrics)))
+            .service(web::resource("/alerts").route(web::get().to(list_alerts)).route(web::post().to(create_alert)))
+            .service(web::resource("/alerts/{id}").route(web::delete().to(delete_alert)))
+
             // Default route - proxy all other requests to Node.js
             .default_service(web::to(proxy_handler))
     })
@@ -346,19 +2283,19 @@ async fn main() -> std::io::Result<()> {
 }
 
 // Helper function to build system metrics from sysinfo data - Updated to match API
-fn build_system_metrics(system: &System, start_time: &SystemTime) -> SystemMetrics {
+fn build_system_metrics(system: &System, start_time: &SystemTime, instance_id: &str) -> SystemMetrics {
     // Updated for sysinfo 0.33.1 API
-    
+
     // Get CPU usage (API changed)
     let cpu_usage = system.global_cpu_usage();
-    
+
     // Get CPU model (API changed)
     let cpu_model = if !system.cpus().is_empty() {
         system.cpus()[0].brand().to_string()
     } else {
         "Unknown CPU".to_string()
     };
-    
+
     SystemMetrics {
         cpu: CpuMetrics {
             usage: cpu_usage,
@@ -384,5 +2321,6 @@ fn build_system_metrics(system: &System, start_time: &SystemTime) -> SystemMetri
             .duration_since(UNIX_EPOCH)
             .unwrap_or(Duration::from_secs(0))
             .as_secs(),
+        instance_id: instance_id.to_string(),
     }
 }